@@ -0,0 +1,327 @@
+use crate::{Result, JumpStrategy, TrampolineHook};
+use crate::registers::CallbackRoutine;
+use crate::mem;
+use std::ffi::c_void;
+use std::ptr::copy_nonoverlapping;
+
+/// What a [`CallbackHook`]'s thunk does once `callback` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackMode {
+    /// Resume the original function through the trampoline's gateway.
+    JmpBack,
+    /// Skip the original function and `ret` straight back to its caller.
+    Function,
+}
+
+/// A hook whose `dst` is a small generated thunk that captures every
+/// general-purpose register and `rflags`/`eflags` into a [`Registers`]
+/// before calling a plain `extern "C"` callback.
+///
+/// This avoids the need to `transmute` the gateway to the hooked function's
+/// exact signature: the callback can inspect and rewrite arguments and the
+/// return value through the captured registers without ever naming that
+/// signature.
+///
+/// [`Registers`]: crate::Registers
+pub struct CallbackHook {
+    thunk: *mut c_void,
+    trampoline: TrampolineHook,
+}
+
+impl CallbackHook {
+    /// Hooks `src` so that `callback` runs with the registers `src` was
+    /// entered with, plus `user_data`.
+    ///
+    /// `len` and `jump` behave as in [`TrampolineHook::hook`]. `mode`
+    /// selects whether `src`'s own code still runs afterwards.
+    pub fn hook(
+        src: *mut c_void,
+        callback: CallbackRoutine,
+        user_data: usize,
+        len: Option<usize>,
+        jump: JumpStrategy,
+        mode: CallbackMode,
+    ) -> Result<Self> {
+        let thunk = mem::alloc_exec(0 as *mut c_void, thunk::CAP)?;
+
+        // The gateway has to exist before the thunk can be assembled, since
+        // `JmpBack` mode jumps straight into it.
+        let trampoline = TrampolineHook::hook(src, thunk, len, jump)?;
+
+        let gateway = match mode {
+            CallbackMode::JmpBack => Some(trampoline.gateway()),
+            CallbackMode::Function => None,
+        };
+
+        let code = thunk::build(callback, user_data, gateway);
+        unsafe { copy_nonoverlapping(code.as_ptr(), thunk as *mut u8, code.len()); }
+
+        Ok(Self { thunk, trampoline })
+    }
+
+    /// Unhooks the function and deallocates the gateway. The thunk stays
+    /// allocated until the hook is dropped.
+    pub fn unhook(&mut self) -> Result<()> {
+        self.trampoline.unhook()
+    }
+
+    /// Returns the state of this hook.
+    pub fn active(&self) -> bool {
+        self.trampoline.active()
+    }
+}
+
+impl Drop for CallbackHook {
+    fn drop(&mut self) {
+        let _ = self.unhook();
+        mem::free(self.thunk, thunk::CAP);
+    }
+}
+
+unsafe impl Sync for CallbackHook { }
+unsafe impl Send for CallbackHook { }
+
+/// Hand-assembled machine code for the register-capturing thunk.
+///
+/// There's no assembler in this crate's dependency tree, so the thunk is
+/// built the same way the jump patches in `hook.rs` are: as raw opcode
+/// bytes, laid out to match the field order of [`crate::Registers`].
+///
+/// `build` has a separate 64-bit implementation per calling convention
+/// (Microsoft x64 vs. System V), since `callback` is `extern "C"` and that
+/// means something different, argument-register- and shadow-space-wise,
+/// on each.
+mod thunk {
+    use crate::registers::CallbackRoutine;
+    use std::ffi::c_void;
+
+    /// Generous fixed size for the generated thunk; real usage is at most
+    /// 116 bytes on 64-bit and 54 bytes on 32-bit.
+    pub(super) const CAP: usize = 256;
+
+    /// Builds the thunk for the Microsoft x64 calling convention: `rcx` =
+    /// `&mut Registers`, `rdx` = `user_data`, plus the 32-byte shadow space
+    /// a callee is entitled to spill its register args into. Without that
+    /// space reserved, such a spill lands directly on the `rax`/`rbx`/
+    /// `rcx`/`rdx` slots (offsets 0-24) of the `Registers` just pushed.
+    #[cfg(all(target_pointer_width = "64", windows))]
+    pub(super) fn build(callback: CallbackRoutine, user_data: usize, gateway: Option<*mut c_void>) -> Vec<u8> {
+        let mut code = Vec::new();
+
+        code.push(0x9C); // pushfq
+        code.extend_from_slice(&[0x41, 0x57]); // push r15
+        code.extend_from_slice(&[0x41, 0x56]); // push r14
+        code.extend_from_slice(&[0x41, 0x55]); // push r13
+        code.extend_from_slice(&[0x41, 0x54]); // push r12
+        code.extend_from_slice(&[0x41, 0x53]); // push r11
+        code.extend_from_slice(&[0x41, 0x52]); // push r10
+        code.extend_from_slice(&[0x41, 0x51]); // push r9
+        code.extend_from_slice(&[0x41, 0x50]); // push r8
+        code.extend_from_slice(&[0x48, 0x83, 0xEC, 0x08]); // sub rsp, 8 (rsp slot)
+        code.push(0x55); // push rbp
+        code.push(0x57); // push rdi
+        code.push(0x56); // push rsi
+        code.push(0x52); // push rdx
+        code.push(0x51); // push rcx
+        code.push(0x53); // push rbx
+        code.push(0x50); // push rax
+
+        // Registers { rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8..r15, rflags }
+        // now sits at [rsp, rsp+136). Fill in the `rsp` slot (offset 56)
+        // with the stack pointer as it was before any of this ran.
+        code.extend_from_slice(&[0x48, 0x8D, 0x84, 0x24, 136, 0x00, 0x00, 0x00]); // lea rax, [rsp+136]
+        code.extend_from_slice(&[0x48, 0x89, 0x44, 0x24, 56]); // mov [rsp+56], rax
+
+        // Microsoft x64: rcx = &mut Registers, rdx = user_data.
+        code.extend_from_slice(&[0x48, 0x89, 0xE1]); // mov rcx, rsp
+        emit_mov_rdx_imm64(&mut code, user_data as u64);
+        emit_mov_rax_imm64(&mut code, callback as usize as u64);
+        code.extend_from_slice(&[0x48, 0x83, 0xEC, 0x20]); // sub rsp, 32 (shadow space)
+        code.extend_from_slice(&[0xFF, 0xD0]); // call rax
+        code.extend_from_slice(&[0x48, 0x83, 0xC4, 0x20]); // add rsp, 32 (drop shadow space)
+
+        code.push(0x58); // pop rax
+        code.push(0x5B); // pop rbx
+        code.push(0x59); // pop rcx
+        code.push(0x5A); // pop rdx
+        code.push(0x5E); // pop rsi
+        code.push(0x5F); // pop rdi
+        code.push(0x5D); // pop rbp
+        code.extend_from_slice(&[0x48, 0x83, 0xC4, 0x08]); // add rsp, 8 (drop rsp slot)
+        code.extend_from_slice(&[0x41, 0x58]); // pop r8
+        code.extend_from_slice(&[0x41, 0x59]); // pop r9
+        code.extend_from_slice(&[0x41, 0x5A]); // pop r10
+        code.extend_from_slice(&[0x41, 0x5B]); // pop r11
+        code.extend_from_slice(&[0x41, 0x5C]); // pop r12
+        code.extend_from_slice(&[0x41, 0x5D]); // pop r13
+        code.extend_from_slice(&[0x41, 0x5E]); // pop r14
+        code.extend_from_slice(&[0x41, 0x5F]); // pop r15
+        code.push(0x9D); // popfq
+
+        match gateway {
+            Some(gateway) => {
+                // jmp [rip+0] with the target inline, so no restored
+                // register is clobbered on the way into the gateway.
+                code.extend_from_slice(&[0xFF, 0x25, 0x00, 0x00, 0x00, 0x00]); // jmp [rip+0]
+                code.extend_from_slice(&(gateway as u64).to_le_bytes());
+            }
+            None => code.push(0xC3), // ret
+        }
+
+        code
+    }
+
+    /// Builds the thunk for the System V x64 calling convention (Linux,
+    /// macOS, *BSD): `rdi` = `&mut Registers`, `rsi` = `user_data`. Unlike
+    /// Microsoft x64, the callee gets no shadow space, and the register
+    /// pushes above already land the stack on a 16-byte boundary at the
+    /// `call`, so nothing extra needs reserving around it.
+    #[cfg(all(target_pointer_width = "64", unix))]
+    pub(super) fn build(callback: CallbackRoutine, user_data: usize, gateway: Option<*mut c_void>) -> Vec<u8> {
+        let mut code = Vec::new();
+
+        code.push(0x9C); // pushfq
+        code.extend_from_slice(&[0x41, 0x57]); // push r15
+        code.extend_from_slice(&[0x41, 0x56]); // push r14
+        code.extend_from_slice(&[0x41, 0x55]); // push r13
+        code.extend_from_slice(&[0x41, 0x54]); // push r12
+        code.extend_from_slice(&[0x41, 0x53]); // push r11
+        code.extend_from_slice(&[0x41, 0x52]); // push r10
+        code.extend_from_slice(&[0x41, 0x51]); // push r9
+        code.extend_from_slice(&[0x41, 0x50]); // push r8
+        code.extend_from_slice(&[0x48, 0x83, 0xEC, 0x08]); // sub rsp, 8 (rsp slot)
+        code.push(0x55); // push rbp
+        code.push(0x57); // push rdi
+        code.push(0x56); // push rsi
+        code.push(0x52); // push rdx
+        code.push(0x51); // push rcx
+        code.push(0x53); // push rbx
+        code.push(0x50); // push rax
+
+        // Registers { rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8..r15, rflags }
+        // now sits at [rsp, rsp+136). Fill in the `rsp` slot (offset 56)
+        // with the stack pointer as it was before any of this ran.
+        code.extend_from_slice(&[0x48, 0x8D, 0x84, 0x24, 136, 0x00, 0x00, 0x00]); // lea rax, [rsp+136]
+        code.extend_from_slice(&[0x48, 0x89, 0x44, 0x24, 56]); // mov [rsp+56], rax
+
+        // System V x64: rdi = &mut Registers, rsi = user_data.
+        code.extend_from_slice(&[0x48, 0x89, 0xE7]); // mov rdi, rsp
+        emit_mov_rsi_imm64(&mut code, user_data as u64);
+        emit_mov_rax_imm64(&mut code, callback as usize as u64);
+        code.extend_from_slice(&[0xFF, 0xD0]); // call rax
+
+        code.push(0x58); // pop rax
+        code.push(0x5B); // pop rbx
+        code.push(0x59); // pop rcx
+        code.push(0x5A); // pop rdx
+        code.push(0x5E); // pop rsi
+        code.push(0x5F); // pop rdi
+        code.push(0x5D); // pop rbp
+        code.extend_from_slice(&[0x48, 0x83, 0xC4, 0x08]); // add rsp, 8 (drop rsp slot)
+        code.extend_from_slice(&[0x41, 0x58]); // pop r8
+        code.extend_from_slice(&[0x41, 0x59]); // pop r9
+        code.extend_from_slice(&[0x41, 0x5A]); // pop r10
+        code.extend_from_slice(&[0x41, 0x5B]); // pop r11
+        code.extend_from_slice(&[0x41, 0x5C]); // pop r12
+        code.extend_from_slice(&[0x41, 0x5D]); // pop r13
+        code.extend_from_slice(&[0x41, 0x5E]); // pop r14
+        code.extend_from_slice(&[0x41, 0x5F]); // pop r15
+        code.push(0x9D); // popfq
+
+        match gateway {
+            Some(gateway) => {
+                // jmp [rip+0] with the target inline, so no restored
+                // register is clobbered on the way into the gateway.
+                code.extend_from_slice(&[0xFF, 0x25, 0x00, 0x00, 0x00, 0x00]); // jmp [rip+0]
+                code.extend_from_slice(&(gateway as u64).to_le_bytes());
+            }
+            None => code.push(0xC3), // ret
+        }
+
+        code
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    fn emit_mov_rax_imm64(code: &mut Vec<u8>, value: u64) {
+        code.extend_from_slice(&[0x48, 0xB8]);
+        code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[cfg(all(target_pointer_width = "64", windows))]
+    fn emit_mov_rdx_imm64(code: &mut Vec<u8>, value: u64) {
+        code.extend_from_slice(&[0x48, 0xBA]);
+        code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[cfg(all(target_pointer_width = "64", unix))]
+    fn emit_mov_rsi_imm64(code: &mut Vec<u8>, value: u64) {
+        code.extend_from_slice(&[0x48, 0xBE]);
+        code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    pub(super) fn build(callback: CallbackRoutine, user_data: usize, gateway: Option<*mut c_void>) -> Vec<u8> {
+        let mut code = Vec::new();
+
+        code.push(0x9C); // pushfd
+        code.extend_from_slice(&[0x83, 0xEC, 0x04]); // sub esp, 4 (esp slot)
+        code.push(0x55); // push ebp
+        code.push(0x57); // push edi
+        code.push(0x56); // push esi
+        code.push(0x52); // push edx
+        code.push(0x51); // push ecx
+        code.push(0x53); // push ebx
+        code.push(0x50); // push eax
+
+        // Registers { eax, ebx, ecx, edx, esi, edi, ebp, esp, eflags } now
+        // sits at [esp, esp+36). Fill in the `esp` slot (offset 28) with
+        // the stack pointer as it was before any of this ran.
+        code.extend_from_slice(&[0x8D, 0x44, 0x24, 36]); // lea eax, [esp+36]
+        code.extend_from_slice(&[0x89, 0x44, 0x24, 28]); // mov [esp+28], eax
+
+        // cdecl: push the arguments right to left, callee cleans nothing.
+        // `esp` moves once `user_data` is pushed, so the `&mut Registers`
+        // base is saved into a scratch register first rather than pushed
+        // directly.
+        code.extend_from_slice(&[0x89, 0xE0]); // mov eax, esp (&mut Registers)
+        emit_push_imm32(&mut code, user_data as u32);
+        code.push(0x50); // push eax (&mut Registers)
+        emit_mov_eax_imm32(&mut code, callback as usize as u32);
+        code.extend_from_slice(&[0xFF, 0xD0]); // call eax
+        code.extend_from_slice(&[0x83, 0xC4, 0x08]); // add esp, 8 (caller cleanup)
+
+        code.push(0x58); // pop eax
+        code.push(0x5B); // pop ebx
+        code.push(0x59); // pop ecx
+        code.push(0x5A); // pop edx
+        code.push(0x5E); // pop esi
+        code.push(0x5F); // pop edi
+        code.push(0x5D); // pop ebp
+        code.extend_from_slice(&[0x83, 0xC4, 0x04]); // add esp, 4 (drop esp slot)
+        code.push(0x9D); // popfd
+
+        match gateway {
+            Some(gateway) => {
+                // push + ret, so no restored register is clobbered on the
+                // way into the gateway.
+                emit_push_imm32(&mut code, gateway as u32);
+                code.push(0xC3); // ret (into the gateway)
+            }
+            None => code.push(0xC3), // ret
+        }
+
+        code
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    fn emit_mov_eax_imm32(code: &mut Vec<u8>, value: u32) {
+        code.push(0xB8);
+        code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    fn emit_push_imm32(code: &mut Vec<u8>, value: u32) {
+        code.push(0x68);
+        code.extend_from_slice(&value.to_le_bytes());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{Error, Result};
+use iced_x86::{BlockEncoder, BlockEncoderOptions, Decoder, DecoderOptions, FlowControl, Instruction, InstructionBlock};
+use std::ffi::c_void;
+use std::slice;
+
+/// Maximum length of a single x86/x64 instruction, per the ISA manual.
+const MAX_INSTRUCTION_LEN: usize = 15;
+
+#[cfg(target_pointer_width = "32")]
+const BITNESS: u32 = 32;
+
+#[cfg(target_pointer_width = "64")]
+const BITNESS: u32 = 64;
+
+/// Disassembles whole instructions starting at `src` until at least `min_len`
+/// bytes have been consumed, returning the total number of bytes spanned by
+/// those instructions.
+///
+/// This guarantees `len` always lands on an instruction boundary, so the
+/// gateway and the restored prologue never contain half an instruction.
+/// Errors out if a single instruction exceeds the 15-byte max, or if a branch
+/// inside the disassembled region targets another instruction inside it,
+/// which would otherwise jump into the middle of the relocated gateway.
+pub(crate) fn prologue_len(src: *mut c_void, min_len: usize) -> Result<usize> {
+    let scan_len = min_len + MAX_INSTRUCTION_LEN;
+    let bytes = unsafe { slice::from_raw_parts(src as *const u8, scan_len) };
+
+    let mut decoder = Decoder::with_ip(BITNESS, bytes, src as u64, DecoderOptions::NONE);
+    let mut insn = Instruction::default();
+    let mut len = 0usize;
+
+    while len < min_len {
+        decoder.decode_out(&mut insn);
+
+        let insn_len = insn.len();
+        if insn.is_invalid() || insn_len == 0 || insn_len > MAX_INSTRUCTION_LEN {
+            return Err(Error::InvalidInstruction);
+        }
+
+        len += insn_len;
+    }
+
+    let mut decoder = Decoder::with_ip(BITNESS, &bytes[..len], src as u64, DecoderOptions::NONE);
+    while decoder.can_decode() {
+        decoder.decode_out(&mut insn);
+
+        match insn.flow_control() {
+            FlowControl::UnconditionalBranch | FlowControl::ConditionalBranch | FlowControl::Call => {
+                let target = insn.near_branch_target();
+                if target >= src as u64 && target < src as u64 + len as u64 {
+                    return Err(Error::BranchInPrologue);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(len)
+}
+
+/// Decodes exactly `len` bytes starting at `src` into whole instructions.
+///
+/// `len` must already land on an instruction boundary, which holds for any
+/// length returned by [`prologue_len`] or validated the same way by the
+/// caller.
+pub(crate) fn decode_instructions(src: *mut c_void, len: usize) -> Result<Vec<Instruction>> {
+    let bytes = unsafe { slice::from_raw_parts(src as *const u8, len) };
+    let mut decoder = Decoder::with_ip(BITNESS, bytes, src as u64, DecoderOptions::NONE);
+
+    let mut instructions = Vec::new();
+    while decoder.can_decode() {
+        let mut insn = Instruction::default();
+        decoder.decode_out(&mut insn);
+
+        if insn.is_invalid() {
+            return Err(Error::InvalidInstruction);
+        }
+
+        instructions.push(insn);
+    }
+
+    Ok(instructions)
+}
+
+/// Re-encodes `instructions` as if they now started at `new_ip`.
+///
+/// `rel32`/`rel8` branches and `[rip+disp]` memory operands are rewritten so
+/// they still point at their original absolute target; a `rel8` branch that
+/// can no longer reach its target from `new_ip` is transparently widened to
+/// the equivalent `rel32` form. Without this, any stolen instruction that
+/// addresses something relative to its own address would read or jump to
+/// the wrong place once moved into the gateway.
+pub(crate) fn relocate(instructions: &[Instruction], new_ip: u64) -> Result<Vec<u8>> {
+    let block = InstructionBlock::new(instructions, new_ip);
+
+    BlockEncoder::encode(BITNESS, block, BlockEncoderOptions::NONE)
+        .map(|result| result.code_buffer)
+        .map_err(|_| Error::InvalidInstruction)
+}
@@ -6,6 +6,18 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     ToSmall,
     InvalidTarget,
+    /// The disassembler hit an instruction it couldn't decode, or one
+    /// longer than the architectural 15-byte maximum, or the stolen
+    /// instructions couldn't be re-encoded at the gateway's address.
+    InvalidInstruction,
+    /// A branch inside the stolen prologue targets another instruction
+    /// inside the same region, which would land in the middle of the
+    /// relocated gateway once the bytes are moved.
+    BranchInPrologue,
+    /// A memory operation failed; carries `GetLastError()` on Windows or
+    /// `errno` on Unix, so [`crate::mem`] stays platform-neutral.
+    Os(i32),
+    #[cfg(windows)]
     Windows(windows::Error)
 }
 
@@ -14,6 +26,10 @@ impl fmt::Display for Error {
         match *self {
             Error::ToSmall => write!(f, "value to small"),
             Error::InvalidTarget => write!(f, "invalid target"),
+            Error::InvalidInstruction => write!(f, "failed to disassemble or relocate prologue"),
+            Error::BranchInPrologue => write!(f, "prologue contains a branch into itself"),
+            Error::Os(code) => write!(f, "os memory operation failed with code {}", code),
+            #[cfg(windows)]
             Error::Windows(ref err) => write!(f, "windows api failed '{}'", err),
         }
     }
@@ -24,11 +40,16 @@ impl error::Error for Error {
         match *self {
             Error::ToSmall => None,
             Error::InvalidTarget => None,
+            Error::InvalidInstruction => None,
+            Error::BranchInPrologue => None,
+            Error::Os(_) => None,
+            #[cfg(windows)]
             Error::Windows(ref err) => Some(err),
         }
     }
 }
 
+#[cfg(windows)]
 impl From<windows::Error> for Error {
     fn from(err: windows::Error) -> Self {
         Error::Windows(err)
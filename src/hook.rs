@@ -1,14 +1,6 @@
 use crate::{Result, Error, JMP_SIZE};
-use crate::bindings::Windows::Win32::System::Memory::{
-    PAGE_PROTECTION_FLAGS,
-    VirtualProtect,
-    VirtualAlloc,
-    VirtualFree,
-    PAGE_EXECUTE_READWRITE,
-    MEM_COMMIT,
-    MEM_RESERVE,
-    MEM_RELEASE
-};
+use crate::disasm;
+use crate::mem;
 use std::ffi::c_void;
 use std::ptr::{copy_nonoverlapping, write_bytes};
 
@@ -27,7 +19,7 @@ use std::ptr::{copy_nonoverlapping, write_bytes};
 /// use crate::bindings::Windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
 /// use std::ffi::c_void;
 /// use std::mem::transmute;
-/// use trampoline::Hook;
+/// use trampoline::{Hook, JumpStrategy};
 ///
 /// mod bindings {
 ///     windows::include_bindings!();
@@ -46,7 +38,8 @@ use std::ptr::{copy_nonoverlapping, write_bytes};
 ///     let hook = Hook::hook(
 ///         src_wgl_swap_buffers as *mut c_void,
 ///         wgl_swap_buffers as *mut c_void,
-///         21
+///         None,
+///         JumpStrategy::Near
 ///     ).unwrap();
 /// }
 /// ```
@@ -54,9 +47,34 @@ pub struct Hook {
     src: *mut c_void,
     len: usize,
     orig_bytes: Vec<u8>,
+    trampoline: Option<*mut c_void>,
     active: bool,
 }
 
+/// Strategy used to redirect `src` to `dst`.
+///
+/// On 32-bit targets a 5-byte relative jump always reaches `dst`, so
+/// `InPlace` and `Near` only differ on 64-bit targets; `Hotpatch` applies
+/// on both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpStrategy {
+    /// Write the 14-byte absolute `FF 25 [rip+0]; dst` jump directly over
+    /// `src`. Simple, but forces `len` to be at least 14 bytes on 64-bit.
+    InPlace,
+    /// Allocate a small executable stub within ±2 GB of `src` holding the
+    /// 14-byte absolute jump to `dst`, and overwrite only 5 bytes of `src`
+    /// with a near `E9` jump to that stub. Lets `len` be as small as 5
+    /// bytes on 64-bit, matching the 32-bit minimum.
+    Near,
+    /// Detour through a hotpatch header: many system DLL functions are
+    /// compiled with 5 bytes of `0x90`/`0xCC` padding followed by a 2-byte
+    /// `mov edi, edi` no-op entry. When that layout is present, write the
+    /// jump to `dst` into the padding and replace the 2-byte entry with a
+    /// short `jmp` to it, leaving the real prologue untouched. Falls back
+    /// to [`JumpStrategy::Near`] when the header isn't there.
+    Hotpatch,
+}
+
 
 /// A 32 or 64 bit trampoline hook.
 ///
@@ -75,7 +93,7 @@ pub struct Hook {
 /// use std::sync::Mutex;
 /// use std::mem::transmute;
 /// use once_cell::sync::Lazy;
-/// use trampoline::TrampolineHook;
+/// use trampoline::{TrampolineHook, JumpStrategy};
 ///
 /// mod bindings {
 ///     windows::include_bindings!();
@@ -109,7 +127,8 @@ pub struct Hook {
 ///     let hook = TrampolineHook::hook(
 ///         src_wgl_swap_buffers as *mut c_void,
 ///         wgl_swap_buffers as *mut c_void,
-///         21
+///         None,
+///         JumpStrategy::Near
 ///     ).unwrap();
 ///
 ///     *HOOK
@@ -119,9 +138,85 @@ pub struct Hook {
 /// ```
 pub struct TrampolineHook {
     gateway: *mut c_void,
+    /// `Some(len)` when `gateway` is a buffer this hook allocated (and
+    /// must free with that length on drop); `None` when it's a pointer
+    /// into `src`'s own untouched prologue, which happens when
+    /// [`JumpStrategy::Hotpatch`] finds a usable header.
+    gateway_len: Option<usize>,
     hook: Hook,
 }
 
+/// On 32-bit a relative jump is always 5 bytes; on 64-bit it depends on the
+/// chosen [`JumpStrategy`].
+#[cfg(target_pointer_width = "32")]
+fn min_len(_jump: JumpStrategy) -> usize {
+    JMP_SIZE
+}
+
+#[cfg(target_pointer_width = "64")]
+fn min_len(jump: JumpStrategy) -> usize {
+    match jump {
+        JumpStrategy::InPlace => JMP_SIZE,
+        JumpStrategy::Near | JumpStrategy::Hotpatch => 5,
+    }
+}
+
+/// Bytes of padding a hotpatchable function is expected to have before it.
+const HOTPATCH_PAD_LEN: usize = 5;
+
+/// Bytes of the `mov edi, edi` no-op entry.
+const HOTPATCH_ENTRY_LEN: usize = 2;
+
+/// Checks for the hotpatch header: `HOTPATCH_PAD_LEN` bytes of `0x90`/`0xCC`
+/// padding immediately followed by the 2-byte `8B FF` (`mov edi, edi`) entry.
+fn has_hotpatch_header(src: *mut c_void) -> bool {
+    let entry = unsafe { std::slice::from_raw_parts(src as *const u8, HOTPATCH_ENTRY_LEN) };
+    if entry != [0x8B, 0xFF] {
+        return false;
+    }
+
+    let pad_addr = (src as usize).wrapping_sub(HOTPATCH_PAD_LEN);
+    let pad = unsafe { std::slice::from_raw_parts(pad_addr as *const u8, HOTPATCH_PAD_LEN) };
+    pad.iter().all(|&b| b == 0x90 || b == 0xCC)
+}
+
+/// Byte granularity `VirtualAlloc` reserves on, used to step the search for
+/// a nearby trampoline region.
+const ALLOC_GRANULARITY: usize = 0x10000;
+
+/// How far from `src` to search for a reservable page, kept comfortably
+/// inside the ±2 GB range a 32-bit displacement can reach.
+const MAX_NEAR_DISTANCE: usize = 0x7FFF_0000;
+
+/// Reserves an executable page within ±2 GB of `src`, so a `rel32` jump
+/// written at `src` can always reach it.
+fn alloc_near(src: *mut c_void, size: usize) -> Result<*mut c_void> {
+    let src = src as usize;
+    let mut offset = ALLOC_GRANULARITY;
+
+    while offset < MAX_NEAR_DISTANCE {
+        for candidate in [src.saturating_add(offset), src.saturating_sub(offset)] {
+            let candidate = candidate - (candidate % ALLOC_GRANULARITY);
+
+            if let Ok(ptr) = mem::alloc_exec(candidate as *mut c_void, size) {
+                // Where the hint isn't honored the mapping can land anywhere,
+                // so only keep it if it's actually in range.
+                let ptr_addr = ptr as usize;
+                let distance = if ptr_addr > src { ptr_addr - src } else { src - ptr_addr };
+                if distance < MAX_NEAR_DISTANCE {
+                    return Ok(ptr);
+                }
+
+                mem::free(ptr, size);
+            }
+        }
+
+        offset += ALLOC_GRANULARITY;
+    }
+
+    Err(Error::InvalidTarget)
+}
+
 impl Hook {
     /// Hooks a function.
     ///
@@ -129,34 +224,42 @@ impl Hook {
     ///
     /// `dst` is the destination of the hook.
     ///
-    /// `len` is the amount of bytes that should be overridden.
-    pub fn hook(src: *mut c_void, dst: *mut c_void, len: usize) -> Result<Self> {
-        if len < JMP_SIZE {
-            return Err(Error::ToSmall);
+    /// `len` is the amount of bytes that should be overridden. Pass `None`
+    /// to have the length-disassembling prologue scanner figure out how
+    /// many whole instructions need to be stolen instead of guessing a
+    /// byte count by hand.
+    ///
+    /// `jump` picks how `src` is redirected; see [`JumpStrategy`].
+    pub fn hook(src: *mut c_void, dst: *mut c_void, len: Option<usize>, jump: JumpStrategy) -> Result<Self> {
+        if jump == JumpStrategy::Hotpatch {
+            if let Some(hook) = Self::hook_hotpatch(src, dst)? {
+                return Ok(hook);
+            }
+
+            return Self::hook(src, dst, len, JumpStrategy::Near);
         }
 
-        let mut protection = PAGE_PROTECTION_FLAGS::default();
+        let len = match len {
+            Some(len) if len < min_len(jump) => return Err(Error::ToSmall),
+            Some(len) => len,
+            None => disasm::prologue_len(src, min_len(jump))?,
+        };
 
-        unsafe {
-            VirtualProtect(
-            src,
-            len,
-            PAGE_EXECUTE_READWRITE,
-            &mut protection
-            )
-        }.ok()?;
+        let protection = mem::protect_rwx(src, len)?;
 
         let mut orig_bytes: Vec<u8> = vec![0x90; len];
         unsafe { copy_nonoverlapping(src, orig_bytes.as_mut_ptr() as *mut c_void, len); }
         unsafe { write_bytes(src, 0x90, len); }
 
+        let mut trampoline: Option<*mut c_void> = None;
+
         if cfg!(target_pointer_width = "32") {
             unsafe { *(src as *mut usize) = 0xE9; }
             unsafe {
                 *(((src as *mut usize) as usize + 1) as *mut usize) =
                     (((dst as *mut isize) as isize - (src as *mut isize) as isize) - 5) as usize;
             }
-        } else if cfg!(target_pointer_width = "64") {
+        } else if cfg!(target_pointer_width = "64") && jump == JumpStrategy::InPlace {
             let mut jmp_bytes: [u8; 14] = [
                 0xFF, 0x25, 0x00, 0x00, 0x00, 0x00,
                 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
@@ -173,20 +276,72 @@ impl Hook {
             }
 
             unsafe { copy_nonoverlapping(jmp_bytes_ptr, src, JMP_SIZE); }
+        } else if cfg!(target_pointer_width = "64") && jump == JumpStrategy::Near {
+            let stub = alloc_near(src, JMP_SIZE)?;
+
+            let mut abs_jmp: [u8; 14] = [
+                0xFF, 0x25, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ];
+
+            unsafe {
+                copy_nonoverlapping(
+                    (&(dst as usize) as *const usize) as *mut c_void,
+                    (abs_jmp.as_mut_ptr() as *mut c_void).offset(6),
+                    8
+                );
+            }
+
+            unsafe { copy_nonoverlapping(abs_jmp.as_ptr(), stub as *mut u8, JMP_SIZE); }
+
+            let mut near_jmp: [u8; 5] = [0xE9, 0x00, 0x00, 0x00, 0x00];
+            let rel = ((stub as isize) - (src as isize) - 5) as i32;
+            near_jmp[1..5].copy_from_slice(&rel.to_le_bytes());
+
+            unsafe { copy_nonoverlapping(near_jmp.as_ptr(), src as *mut u8, near_jmp.len()); }
+
+            trampoline = Some(stub);
         } else {
             return Err(Error::InvalidTarget);
         }
 
-        unsafe {
-            VirtualProtect(
-                src,
-                len,
-                protection,
-                &mut protection
-            )
-        }.ok()?;
-
-        Ok(Self { src, len, orig_bytes, active: true })
+        mem::restore_protection(src, len, protection)?;
+
+        Ok(Self { src, len, orig_bytes, trampoline, active: true })
+    }
+
+    /// Hooks `src` through its hotpatch header, if it has one.
+    ///
+    /// Returns `Ok(None)` without touching any memory when the header
+    /// isn't present, so the caller can fall back to another strategy.
+    fn hook_hotpatch(src: *mut c_void, dst: *mut c_void) -> Result<Option<Self>> {
+        if !has_hotpatch_header(src) {
+            return Ok(None);
+        }
+
+        let pad_addr = ((src as usize) - HOTPATCH_PAD_LEN) as *mut c_void;
+        let region_len = HOTPATCH_PAD_LEN + HOTPATCH_ENTRY_LEN;
+
+        let protection = mem::protect_rwx(pad_addr, region_len)?;
+
+        // Only the 2-byte entry needs to be restored on unhook; once it's
+        // gone nothing jumps into the padding jump anymore.
+        let mut orig_bytes: Vec<u8> = vec![0x90; HOTPATCH_ENTRY_LEN];
+        unsafe { copy_nonoverlapping(src, orig_bytes.as_mut_ptr() as *mut c_void, HOTPATCH_ENTRY_LEN); }
+
+        let mut pad_jmp: [u8; 5] = [0xE9, 0x00, 0x00, 0x00, 0x00];
+        let pad_rel = (((dst as isize) - (pad_addr as isize)) - 5) as i32;
+        pad_jmp[1..5].copy_from_slice(&pad_rel.to_le_bytes());
+        unsafe { copy_nonoverlapping(pad_jmp.as_ptr(), pad_addr as *mut u8, pad_jmp.len()); }
+
+        // `jmp $-5`: a short jump from the entry back to the padding jump.
+        let entry_rel = (((pad_addr as isize) - (src as isize + HOTPATCH_ENTRY_LEN as isize))) as i8;
+        let entry_jmp: [u8; 2] = [0xEB, entry_rel as u8];
+        unsafe { copy_nonoverlapping(entry_jmp.as_ptr(), src as *mut u8, entry_jmp.len()); }
+
+        mem::restore_protection(pad_addr, region_len, protection)?;
+
+        Ok(Some(Self { src, len: HOTPATCH_ENTRY_LEN, orig_bytes, trampoline: None, active: true }))
     }
 
     /// Unhooks the function.
@@ -195,16 +350,7 @@ impl Hook {
             return Ok(());
         }
 
-        let mut protection = PAGE_PROTECTION_FLAGS::default();
-
-        unsafe {
-            VirtualProtect(
-                self.src,
-                self.len,
-                PAGE_EXECUTE_READWRITE,
-                &mut protection
-            )
-        }.ok()?;
+        let protection = mem::protect_rwx(self.src, self.len)?;
 
         unsafe {
             copy_nonoverlapping(
@@ -214,14 +360,11 @@ impl Hook {
             );
         }
 
-        unsafe {
-            VirtualProtect(
-                self.src,
-                self.len,
-                protection,
-                &mut protection
-            )
-        }.ok()?;
+        mem::restore_protection(self.src, self.len, protection)?;
+
+        if let Some(trampoline) = self.trampoline.take() {
+            mem::free(trampoline, JMP_SIZE);
+        }
 
         self.active = false;
         Ok(())
@@ -249,28 +392,48 @@ impl TrampolineHook {
     ///
     /// `dst` is the destination of the hook.
     ///
-    /// `len` is the amount of bytes that should be overridden.
-    pub fn hook(src: *mut c_void, dst: *mut c_void, len: usize) -> Result<Self> {
-        if len < JMP_SIZE {
-            return Err(Error::ToSmall);
+    /// `len` is the amount of bytes that should be overridden. Pass `None`
+    /// to have the length-disassembling prologue scanner figure out how
+    /// many whole instructions need to be stolen instead of guessing a
+    /// byte count by hand.
+    ///
+    /// `jump` picks how `src` is redirected; see [`JumpStrategy`].
+    pub fn hook(src: *mut c_void, dst: *mut c_void, len: Option<usize>, jump: JumpStrategy) -> Result<Self> {
+        if jump == JumpStrategy::Hotpatch && has_hotpatch_header(src) {
+            // The entry's own prologue stays untouched, so the gateway is
+            // simply the code right after the 2-byte `mov edi, edi`.
+            let gateway = ((src as usize) + HOTPATCH_ENTRY_LEN) as *mut c_void;
+            let hook = Hook::hook(src, dst, len, jump)?;
+            return Ok(Self { gateway, gateway_len: None, hook });
         }
 
-        let gateway = unsafe {
-            VirtualAlloc(
-                0 as *mut c_void,
-                len + JMP_SIZE,
-                MEM_COMMIT | MEM_RESERVE,
-                PAGE_EXECUTE_READWRITE
-            )
+        let jump = if jump == JumpStrategy::Hotpatch { JumpStrategy::Near } else { jump };
+
+        let len = match len {
+            Some(len) if len < min_len(jump) => return Err(Error::ToSmall),
+            Some(len) => len,
+            None => disasm::prologue_len(src, min_len(jump))?,
         };
 
-        unsafe { copy_nonoverlapping(src, gateway, len); }
+        let instructions = disasm::decode_instructions(src, len)?;
+
+        // Relocating a rip-relative instruction can widen a 2-byte `rel8`
+        // conditional branch into a 6-byte `rel32` one (+4), so the gateway
+        // needs more room than the stolen bytes alone.
+        let gateway_cap = len + JMP_SIZE + instructions.len() * 4;
+
+        let gateway = mem::alloc_exec(0 as *mut c_void, gateway_cap)?;
+
+        let relocated = disasm::relocate(&instructions, gateway as u64)?;
+        unsafe { copy_nonoverlapping(relocated.as_ptr(), gateway as *mut u8, relocated.len()); }
+
+        let gateway_jmp_back = ((gateway as usize) + relocated.len()) as *mut c_void;
 
         if cfg!(target_pointer_width = "32") {
-            unsafe { *(((gateway as *mut usize) as usize + len) as *mut usize) = 0xE9; }
+            unsafe { *(((gateway_jmp_back as *mut usize) as usize) as *mut usize) = 0xE9; }
             unsafe {
-                *(((gateway as *mut usize) as usize + len + 1) as *mut usize) =
-                    (((src as *mut isize) as isize - (gateway as *mut isize) as isize) - 5) as usize;
+                *(((gateway_jmp_back as *mut usize) as usize + 1) as *mut usize) =
+                    (((src as *mut isize) as isize + len as isize - (gateway_jmp_back as *mut isize) as isize) - 5) as usize;
             }
         } else if cfg!(target_pointer_width = "64") {
             let mut jmp_bytes: [u8; 14] = [
@@ -279,10 +442,11 @@ impl TrampolineHook {
             ];
 
             let jmp_bytes_ptr = jmp_bytes.as_mut_ptr() as *mut c_void;
+            let jmp_back_target = (src as usize) + len;
 
             unsafe {
                 copy_nonoverlapping(
-                    ((&((src as usize) + len)) as *const usize) as *mut c_void,
+                    ((&jmp_back_target) as *const usize) as *mut c_void,
                     jmp_bytes_ptr.offset(6),
                     8
                 );
@@ -291,7 +455,7 @@ impl TrampolineHook {
             unsafe {
                 copy_nonoverlapping(
                     jmp_bytes_ptr,
-                    ((gateway as usize) + len) as *mut c_void,
+                    gateway_jmp_back,
                     JMP_SIZE
                 );
             }
@@ -299,17 +463,20 @@ impl TrampolineHook {
             return Err(Error::InvalidTarget);
         }
 
-        let hook = Hook::hook(src, dst, len)?;
-        Ok(Self { gateway, hook })
+        let hook = Hook::hook(src, dst, Some(len), jump)?;
+        Ok(Self { gateway, gateway_len: Some(gateway_cap), hook })
     }
 
-    /// Unhooks the function and deallocates the gateway.
+    /// Unhooks the function and deallocates the gateway, if it owns one.
     pub fn unhook(&mut self) -> Result<()> {
         if !self.active() {
             return Ok(());
         }
 
-        unsafe { VirtualFree(self.gateway, 0, MEM_RELEASE) }.ok()?;
+        if let Some(len) = self.gateway_len.take() {
+            mem::free(self.gateway, len);
+        }
+
         self.hook.unhook()?;
         Ok(())
     }
@@ -28,7 +28,7 @@
 //! use std::sync::Mutex;
 //! use std::mem::transmute;
 //! use once_cell::sync::Lazy;
-//! use trampoline::TrampolineHook;
+//! use trampoline::{TrampolineHook, JumpStrategy};
 //!
 //! mod bindings {
 //!     windows::include_bindings!();
@@ -62,7 +62,8 @@
 //!     let hook = TrampolineHook::hook(
 //!         src_wgl_swap_buffers as *mut c_void,
 //!         wgl_swap_buffers as *mut c_void,
-//!         21
+//!         None,
+//!         JumpStrategy::Near
 //!     ).unwrap();
 //!
 //!     *HOOK
@@ -72,11 +73,18 @@
 //! ```
 
 pub use error::*;
-pub use hook::{TrampolineHook, Hook};
+pub use hook::{TrampolineHook, Hook, JumpStrategy};
+pub use callback::{CallbackHook, CallbackMode};
+pub use registers::{Registers, CallbackRoutine};
 
+mod callback;
+mod disasm;
 mod error;
 mod hook;
+mod mem;
+mod registers;
 
+#[cfg(windows)]
 mod bindings {
     windows::include_bindings!();
 }
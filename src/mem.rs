@@ -0,0 +1,19 @@
+//! Platform-specific memory operations used by [`crate::Hook`] and
+//! [`crate::TrampolineHook`]: making a range of `src` writable-and-
+//! executable, restoring it afterwards, and allocating executable pages
+//! for gateways, near-trampoline stubs and callback thunks.
+//!
+//! Everything above this module works only with [`crate::Result`] and the
+//! platform-neutral [`Protection`] token, so `hook.rs` and `callback.rs`
+//! never need a `#[cfg(windows)]`/`#[cfg(unix)]` of their own.
+
+#[cfg(windows)]
+mod windows;
+#[cfg(unix)]
+mod unix;
+
+#[cfg(windows)]
+pub(crate) use self::windows::{Protection, protect_rwx, restore_protection, alloc_exec, free};
+
+#[cfg(unix)]
+pub(crate) use self::unix::{Protection, protect_rwx, restore_protection, alloc_exec, free};
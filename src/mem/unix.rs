@@ -0,0 +1,109 @@
+use crate::{Error, Result};
+use std::ffi::c_void;
+
+/// The protection a range had before [`protect_rwx`] changed it.
+pub(crate) type Protection = libc::c_int;
+
+fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Rounds `addr` down and `len` up to whole pages, since `mprotect` only
+/// operates on page-aligned ranges.
+fn page_align(addr: usize, len: usize) -> (usize, usize) {
+    let page = page_size();
+    let aligned_addr = addr - (addr % page);
+    let slack = addr - aligned_addr;
+    let aligned_len = (len + slack + page - 1) / page * page;
+    (aligned_addr, aligned_len)
+}
+
+/// Makes `[addr, addr + len)` read-write-execute, returning the protection
+/// to put back with [`restore_protection`].
+///
+/// Unlike `VirtualProtect`, `mprotect` doesn't hand back the previous
+/// protection, so this always assumes a typical read+execute code page.
+pub(crate) fn protect_rwx(addr: *mut c_void, len: usize) -> Result<Protection> {
+    let (aligned_addr, aligned_len) = page_align(addr as usize, len);
+
+    let rc = unsafe {
+        libc::mprotect(
+            aligned_addr as *mut c_void,
+            aligned_len,
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC
+        )
+    };
+
+    if rc != 0 {
+        return Err(Error::Os(errno()));
+    }
+
+    Ok(libc::PROT_READ | libc::PROT_EXEC)
+}
+
+/// Restores the protection a range had before [`protect_rwx`].
+pub(crate) fn restore_protection(addr: *mut c_void, len: usize, protection: Protection) -> Result<()> {
+    let (aligned_addr, aligned_len) = page_align(addr as usize, len);
+
+    let rc = unsafe { libc::mprotect(aligned_addr as *mut c_void, aligned_len, protection) };
+
+    if rc != 0 {
+        return Err(Error::Os(errno()));
+    }
+
+    Ok(())
+}
+
+/// Maps `len` bytes of anonymous executable memory. On Linux, `hint` pins
+/// the mapping to that exact address (used to probe for a near-trampoline
+/// region) and fails rather than silently relocating if it's taken.
+///
+/// `MAP_FIXED_NOREPLACE` isn't available outside Linux, and the
+/// alternative, `MAP_FIXED`, can silently clobber an existing mapping
+/// instead of failing, which `alloc_near`'s probing relies on not
+/// happening. So elsewhere the hint is dropped and the kernel always
+/// chooses the address; pass a null pointer there too, since it means the
+/// same thing.
+pub(crate) fn alloc_exec(hint: *mut c_void, len: usize) -> Result<*mut c_void> {
+    #[cfg(target_os = "linux")]
+    let (hint, flags) = {
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        if !hint.is_null() {
+            flags |= libc::MAP_FIXED_NOREPLACE;
+        }
+        (hint, flags)
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let (hint, flags) = {
+        let _ = hint;
+        (0 as *mut c_void, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS)
+    };
+
+    let ptr = unsafe {
+        libc::mmap(
+            hint,
+            len,
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            flags,
+            -1,
+            0
+        )
+    };
+
+    if ptr == libc::MAP_FAILED {
+        return Err(Error::Os(errno()));
+    }
+
+    Ok(ptr)
+}
+
+/// Releases memory obtained from [`alloc_exec`]. `len` must match the
+/// length originally mapped, since `munmap` needs it.
+pub(crate) fn free(ptr: *mut c_void, len: usize) {
+    unsafe { libc::munmap(ptr, len); }
+}
@@ -0,0 +1,52 @@
+use crate::{Error, Result};
+use crate::bindings::Windows::Win32::System::Memory::{
+    PAGE_PROTECTION_FLAGS,
+    VirtualProtect,
+    VirtualAlloc,
+    VirtualFree,
+    PAGE_EXECUTE_READWRITE,
+    MEM_COMMIT,
+    MEM_RESERVE,
+    MEM_RELEASE
+};
+use std::ffi::c_void;
+
+/// The protection a range had before [`protect_rwx`] changed it.
+pub(crate) type Protection = PAGE_PROTECTION_FLAGS;
+
+/// Makes `[addr, addr + len)` read-write-execute, returning its previous
+/// protection so the caller can put it back with [`restore_protection`].
+pub(crate) fn protect_rwx(addr: *mut c_void, len: usize) -> Result<Protection> {
+    let mut protection = PAGE_PROTECTION_FLAGS::default();
+    unsafe { VirtualProtect(addr, len, PAGE_EXECUTE_READWRITE, &mut protection) }.ok()?;
+    Ok(protection)
+}
+
+/// Restores the protection a range had before [`protect_rwx`].
+pub(crate) fn restore_protection(addr: *mut c_void, len: usize, protection: Protection) -> Result<()> {
+    let mut previous = protection;
+    unsafe { VirtualProtect(addr, len, previous, &mut previous) }.ok()?;
+    Ok(())
+}
+
+/// Reserves and commits `len` bytes of executable memory. `hint` pins the
+/// allocation to that exact address (used to probe for a near-trampoline
+/// region); pass a null pointer to let the OS choose.
+pub(crate) fn alloc_exec(hint: *mut c_void, len: usize) -> Result<*mut c_void> {
+    let ptr = unsafe { VirtualAlloc(hint, len, MEM_COMMIT | MEM_RESERVE, PAGE_EXECUTE_READWRITE) };
+
+    if ptr.is_null() {
+        // `std::io::Error::last_os_error` reads `GetLastError()` under the
+        // hood, so this doesn't depend on `GetLastError` being present in
+        // the project's `windows::build!` bindings list.
+        return Err(Error::Os(std::io::Error::last_os_error().raw_os_error().unwrap_or(0)));
+    }
+
+    Ok(ptr)
+}
+
+/// Releases memory obtained from [`alloc_exec`]. `len` is unused on
+/// Windows, since `VirtualFree(MEM_RELEASE)` only needs the base address.
+pub(crate) fn free(ptr: *mut c_void, _len: usize) {
+    unsafe { VirtualFree(ptr, 0, MEM_RELEASE) };
+}
@@ -0,0 +1,58 @@
+/// Snapshot of the general-purpose registers and flags captured when a
+/// [`crate::CallbackHook`]'s thunk runs.
+///
+/// The callback receives a `*mut Registers` pointing at this snapshot on
+/// the stack; mutating a field before returning changes the value that
+/// gets restored into the real register before control resumes.
+///
+/// `rsp` reflects the stack pointer as it was at the moment `src` was
+/// entered, before the thunk's own prologue ran. Writing to it has no
+/// effect, since the thunk restores registers with `pop`, not by moving
+/// the real stack pointer.
+#[cfg(target_pointer_width = "64")]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rflags: u64,
+}
+
+/// Snapshot of the general-purpose registers and flags captured when a
+/// [`crate::CallbackHook`]'s thunk runs.
+///
+/// See the 64-bit [`Registers`] for the full description; `esp` behaves the
+/// same way `rsp` does there.
+#[cfg(target_pointer_width = "32")]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Registers {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub eflags: u32,
+}
+
+/// A user callback invoked by a [`crate::CallbackHook`]'s generated thunk
+/// with a pointer to the captured [`Registers`] and the `user_data` that
+/// was passed to [`crate::CallbackHook::hook`].
+pub type CallbackRoutine = extern "C" fn(regs: *mut Registers, user_data: usize);